@@ -1,24 +1,24 @@
 use std::error::Error;
-use std::mem::transmute;
+use std::convert::TryFrom;
 use std::fmt;
 use bit_iterator::BitIterator;
+use zerocopy::{AsBytes, FromBytes, Ref, Unaligned};
+use zerocopy::byteorder::network_endian::{U16, U32};
 
 pub const HEADER_SIZE: usize = 20;
 
-macro_rules! u8_to_unsigned_be {
-    ($src:ident, $start:expr, $end:expr, $t:ty) => ({
-        let mut result: $t = 0;
-        for i in (0usize .. $end - $start + 1).rev() {
-            result = result | $src[$start+i] as $t << i*8;
-        }
-        result
-    })
-}
+/// Upper bound on the number of extensions a single packet may carry.
+/// A malicious peer could otherwise chain an arbitrary number of tiny
+/// extensions, each allocating its own `Vec`, to exhaust memory.
+const MAX_EXTENSIONS: usize = 8;
+
+/// Upper bound on the total size, in bytes, of all extensions combined.
+const MAX_EXTENSION_BYTES: usize = 1024;
 
 macro_rules! make_getter {
-    ($name:ident, $t:ty, $m:ident) => {
+    ($name:ident, $t:ty) => {
         pub fn $name(&self) -> $t {
-            $m::from_be(self.header.$name)
+            self.header.$name.get()
         }
     }
 }
@@ -26,15 +26,79 @@ macro_rules! make_getter {
 macro_rules! make_setter {
     ($fn_name:ident, $field:ident, $t: ty) => {
         pub fn $fn_name(&mut self, new: $t) {
-            self.header.$field = new.to_be();
+            self.header.$field.set(new);
         }
     }
 }
 
+/// A bounds-checked cursor over a byte slice, used to replace manual
+/// index/length arithmetic when walking variable-length parts of a packet
+/// (such as the extension chain). Every read is fallible by construction:
+/// it returns `ParseError::InvalidPacketLength` rather than panicking or
+/// reading out of bounds when the slice is exhausted.
+struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(buf: &'a [u8]) -> Decoder<'a> {
+        Decoder { buf: buf, pos: 0 }
+    }
+
+    /// Number of bytes left to read.
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    fn decode_u8(&mut self) -> Result<u8, ParseError> {
+        let byte = match self.buf.get(self.pos) {
+            Some(&byte) => byte,
+            None => return Err(ParseError::InvalidPacketLength),
+        };
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn decode_vec(&mut self, len: usize) -> Result<Vec<u8>, ParseError> {
+        if self.remaining() < len {
+            return Err(ParseError::InvalidPacketLength);
+        }
+        let vec = self.buf[self.pos..self.pos + len].to_vec();
+        self.pos += len;
+        Ok(vec)
+    }
+}
+
+/// An append-only byte builder, the encoding counterpart of `Decoder`.
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    fn with_capacity(capacity: usize) -> Encoder {
+        Encoder { buf: Vec::with_capacity(capacity) }
+    }
+
+    fn u8(&mut self, byte: u8) {
+        self.buf.push(byte);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn into_vec(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
 #[derive(Debug)]
 pub enum ParseError {
     InvalidExtensionLength,
     InvalidPacketLength,
+    InvalidPacketType,
+    TooManyExtensions,
     UnsupportedVersion
 }
 
@@ -50,6 +114,8 @@ impl Error for ParseError {
         match *self {
             InvalidExtensionLength => "Invalid extension length (must be a non-zero multiple of 4)",
             InvalidPacketLength => "The packet is too small",
+            InvalidPacketType => "Unknown packet type",
+            TooManyExtensions => "Too many extensions, or extensions too large, in a single packet",
             UnsupportedVersion => "Unsupported packet version",
         }
     }
@@ -64,9 +130,45 @@ pub enum PacketType {
     Syn   = 4,
 }
 
+impl TryFrom<u8> for PacketType {
+    type Error = ();
+
+    fn try_from(v: u8) -> Result<PacketType, ()> {
+        match v {
+            0 => Ok(PacketType::Data),
+            1 => Ok(PacketType::Fin),
+            2 => Ok(PacketType::State),
+            3 => Ok(PacketType::Reset),
+            4 => Ok(PacketType::Syn),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(PartialEq,Eq,Debug,Clone,Copy)]
 pub enum ExtensionType {
-    SelectiveAck = 1,
+    SelectiveAck,
+    /// An extension kind this version of the library doesn't know the
+    /// meaning of. Kept around, rather than discarded, so that `Packet::bytes`
+    /// can faithfully reconstruct the extension chain of a packet we merely
+    /// relay.
+    Unknown(u8),
+}
+
+impl ExtensionType {
+    fn from_u8(kind: u8) -> ExtensionType {
+        match kind {
+            1 => ExtensionType::SelectiveAck,
+            other => ExtensionType::Unknown(other),
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            ExtensionType::SelectiveAck => 1,
+            ExtensionType::Unknown(kind) => kind,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -96,17 +198,17 @@ impl Extension {
     }
 }
 
-#[derive(Clone,Copy)]
-#[packed]
+#[derive(Clone, Copy, FromBytes, AsBytes, Unaligned)]
+#[repr(C, packed)]
 struct PacketHeader {
     type_ver: u8, // type: u4, ver: u4
     extension: u8,
-    connection_id: u16,
-    timestamp_microseconds: u32,
-    timestamp_difference_microseconds: u32,
-    wnd_size: u32,
-    seq_nr: u16,
-    ack_nr: u16,
+    connection_id: U16,
+    timestamp_microseconds: U32,
+    timestamp_difference_microseconds: U32,
+    wnd_size: U32,
+    seq_nr: U16,
+    ack_nr: U16,
 }
 
 impl PacketHeader {
@@ -117,7 +219,9 @@ impl PacketHeader {
     }
 
     pub fn get_type(&self) -> PacketType {
-        unsafe { transmute(self.type_ver >> 4) }
+        // `decode` rejects any type nibble that doesn't map to a `PacketType`
+        // before a `PacketHeader` is ever constructed, so this can't fail.
+        PacketType::try_from(self.type_ver >> 4).expect("type nibble validated at decode time")
     }
 
     pub fn get_version(&self) -> u8 {
@@ -126,8 +230,7 @@ impl PacketHeader {
 
     /// Return packet header as a slice of bytes.
     pub fn bytes(&self) -> &[u8] {
-        let buf: &[u8; HEADER_SIZE] = unsafe { transmute(self) };
-        return &buf[..];
+        self.as_bytes()
     }
 
     pub fn len(&self) -> usize {
@@ -137,16 +240,20 @@ impl PacketHeader {
     /// Read byte buffer and return corresponding packet header.
     /// It assumes the fields are in network (big-endian) byte order,
     /// preserving it.
-    pub fn decode(buf: &[u8]) -> PacketHeader {
-        PacketHeader {
-            type_ver: buf[0],
-            extension: buf[1],
-            connection_id: u8_to_unsigned_be!(buf, 2, 3, u16),
-            timestamp_microseconds: u8_to_unsigned_be!(buf, 4, 7, u32),
-            timestamp_difference_microseconds: u8_to_unsigned_be!(buf, 8, 11, u32),
-            wnd_size: u8_to_unsigned_be!(buf, 12, 15, u32),
-            seq_nr: u8_to_unsigned_be!(buf, 16, 17, u16),
-            ack_nr: u8_to_unsigned_be!(buf, 18, 19, u16),
+    ///
+    /// This stays on `zerocopy::Ref` rather than `Decoder`: the header is a
+    /// single fixed-layout `#[repr(C, packed)]` struct, which is exactly what
+    /// `Ref::new_from_prefix` is for. `Decoder` is scoped to the
+    /// variable-length parts of a packet (the extension chain and payload),
+    /// where there's no fixed struct to zero-copy onto.
+    pub fn decode(buf: &[u8]) -> Result<PacketHeader, ParseError> {
+        if PacketType::try_from(buf[0] >> 4).is_err() {
+            return Err(ParseError::InvalidPacketType);
+        }
+
+        match Ref::<_, PacketHeader>::new_from_prefix(buf) {
+            Some((header, _rest)) => Ok(*header),
+            None => Err(ParseError::InvalidPacketLength),
         }
     }
 }
@@ -158,14 +265,14 @@ impl fmt::Debug for PacketHeader {
                 timestamp_difference_microseconds: {}, wnd_size: {}, \
                 seq_nr: {}, ack_nr: {})",
                 self.get_type(),
-                u8::from_be(self.get_version()),
-                u8::from_be(self.extension),
-                u16::from_be(self.connection_id),
-                u32::from_be(self.timestamp_microseconds),
-                u32::from_be(self.timestamp_difference_microseconds),
-                u32::from_be(self.wnd_size),
-                u16::from_be(self.seq_nr),
-                u16::from_be(self.ack_nr),
+                self.get_version(),
+                self.extension,
+                self.connection_id.get(),
+                self.timestamp_microseconds.get(),
+                self.timestamp_difference_microseconds.get(),
+                self.wnd_size.get(),
+                self.seq_nr.get(),
+                self.ack_nr.get(),
         )
     }
 }
@@ -183,12 +290,12 @@ impl Packet {
             header: PacketHeader {
                 type_ver: (PacketType::Data as u8) << 4 | 1,
                 extension: 0,
-                connection_id: 0,
-                timestamp_microseconds: 0,
-                timestamp_difference_microseconds: 0,
-                wnd_size: 0,
-                seq_nr: 0,
-                ack_nr: 0,
+                connection_id: U16::new(0),
+                timestamp_microseconds: U32::new(0),
+                timestamp_difference_microseconds: U32::new(0),
+                wnd_size: U32::new(0),
+                seq_nr: U16::new(0),
+                ack_nr: U16::new(0),
             },
             extensions: Vec::new(),
             payload: Vec::new(),
@@ -205,12 +312,12 @@ impl Packet {
         self.header.get_type()
     }
 
-    make_getter!(seq_nr, u16, u16);
-    make_getter!(ack_nr, u16, u16);
-    make_getter!(connection_id, u16, u16);
-    make_getter!(wnd_size, u32, u32);
-    make_getter!(timestamp_microseconds, u32, u32);
-    make_getter!(timestamp_difference_microseconds, u32, u32);
+    make_getter!(seq_nr, u16);
+    make_getter!(ack_nr, u16);
+    make_getter!(connection_id, u16);
+    make_getter!(wnd_size, u32);
+    make_getter!(timestamp_microseconds, u32);
+    make_getter!(timestamp_difference_microseconds, u32);
 
     make_setter!(set_seq_nr, seq_nr, u16);
     make_setter!(set_ack_nr, ack_nr, u16);
@@ -219,53 +326,64 @@ impl Packet {
     make_setter!(set_timestamp_microseconds, timestamp_microseconds, u32);
     make_setter!(set_timestamp_difference_microseconds, timestamp_difference_microseconds, u32);
 
-    /// Set Selective ACK field in packet header and add appropriate data.
+    /// Append an extension of the given kind to the packet.
     ///
-    /// The length of the SACK extension is expressed in bytes, which
-    /// must be a multiple of 4 and at least 4.
-    pub fn set_sack(&mut self, bv: Vec<u8>) {
-        // The length of the SACK extension is expressed in bytes, which
-        // must be a multiple of 4 and at least 4.
-        assert!(bv.len() >= 4);
-        assert!(bv.len() % 4 == 0);
+    /// The length of `data` is expressed in bytes, which must be a
+    /// multiple of 4 and at least 4.
+    pub fn add_extension(&mut self, kind: u8, data: Vec<u8>) {
+        assert!(data.len() >= 4);
+        assert!(data.len() % 4 == 0);
+
+        // `header.extension` names only the *first* extension in the chain;
+        // later extensions are linked together via each extension's own
+        // "next kind" byte, not folded into the header.
+        if self.extensions.is_empty() {
+            self.header.extension = kind;
+        }
 
         let extension = Extension {
-            ty: ExtensionType::SelectiveAck,
-            data: bv,
+            ty: ExtensionType::from_u8(kind),
+            data: data,
         };
         self.extensions.push(extension);
-        self.header.extension |= ExtensionType::SelectiveAck as u8;
+    }
+
+    /// Iterate over the extensions of the given kind attached to this packet.
+    pub fn extensions_of_type<'a>(&'a self, ty: ExtensionType) -> impl Iterator<Item = &'a Extension> + 'a {
+        self.extensions.iter().filter(move |ext| ext.ty == ty)
+    }
+
+    /// Append a Selective ACK extension with the given data. The packet
+    /// header's extension field names it only if it is the first extension
+    /// added; otherwise it is linked from the previous extension.
+    ///
+    /// The length of the SACK extension is expressed in bytes, which
+    /// must be a multiple of 4 and at least 4.
+    pub fn set_sack(&mut self, bv: Vec<u8>) {
+        self.add_extension(ExtensionType::SelectiveAck.to_u8(), bv);
     }
 
     pub fn bytes(&self) -> Vec<u8> {
-        use std::ptr;
-        let mut buf: Vec<u8> = Vec::with_capacity(self.len());
+        let mut encoder = Encoder::with_capacity(self.len());
 
         // Copy header
-        unsafe {
-            ptr::copy(self.header.bytes().as_ptr(), buf.as_mut_ptr(), self.header.len());
-            buf.set_len(self.header.len());
-        }
+        encoder.bytes(self.header.as_bytes());
 
         // Copy extensions
         let mut extensions = self.extensions.iter().peekable();
         while let Some(extension) = extensions.next() {
             // next extension id
             match extensions.peek() {
-                None => buf.push(0u8),
-                Some(next) => buf.push(next.ty as u8),
+                None => encoder.u8(0),
+                Some(next) => encoder.u8(next.ty.to_u8()),
             }
-            buf.extend(extension.to_bytes());
+            encoder.bytes(&extension.to_bytes());
         }
 
         // Copy payload
-        unsafe {
-            let buf_len = buf.len();
-            ptr::copy(self.payload.as_ptr(), buf.as_mut_ptr().offset(buf.len() as isize), self.payload.len());
-            buf.set_len(buf_len + self.payload.len());
-        }
+        encoder.bytes(&self.payload);
 
-        return buf;
+        encoder.into_vec()
     }
 
     pub fn len(&self) -> usize {
@@ -282,65 +400,70 @@ impl Packet {
         if buf.len() < HEADER_SIZE {
             return Err(ParseError::InvalidPacketLength);
         }
-        let header = PacketHeader::decode(buf);
+        let header = match PacketHeader::decode(buf) {
+            Ok(header) => header,
+            Err(e) => return Err(e),
+        };
 
         if header.get_version() != 1 {
             return Err(ParseError::UnsupportedVersion);
         }
 
         let mut extensions = Vec::new();
-        let mut idx = HEADER_SIZE;
+        let mut cursor = Decoder::new(&buf[HEADER_SIZE..]);
         let mut kind = header.extension;
+        let mut total_extension_bytes = 0usize;
 
-        if buf.len() == HEADER_SIZE && header.extension != 0 {
-            return Err(ParseError::InvalidExtensionLength);
-        }
-
-        // Consume known extensions and skip over unknown ones
-        while idx < buf.len() && kind != 0 {
-            if buf.len() < idx + 2 {
+        // Walk the extension chain, keeping every extension (even ones of
+        // an unrecognised kind) so that re-encoding via `bytes()` round-trips.
+        while cursor.remaining() > 0 && kind != 0 {
+            if cursor.remaining() < 2 {
                 return Err(ParseError::InvalidPacketLength);
             }
-            let len = buf[idx + 1] as usize;
-            let extension_start = idx + 2;
-            let payload_start = extension_start + len;
+            let next_kind = match cursor.decode_u8() {
+                Ok(next_kind) => next_kind,
+                Err(e) => return Err(e),
+            };
+            let len = match cursor.decode_u8() {
+                Ok(len) => len as usize,
+                Err(e) => return Err(e),
+            };
 
             // Check validity of extension length:
             // - non-zero,
             // - multiple of 4,
             // - does not exceed packet length
-            if len == 0 || len % 4 != 0 || payload_start > buf.len() {
+            if len == 0 || len % 4 != 0 || cursor.remaining() < len {
                 return Err(ParseError::InvalidExtensionLength);
             }
 
-            if kind == ExtensionType::SelectiveAck as u8 { // or more generally, a known kind
-                let extension = Extension {
-                    ty: ExtensionType::SelectiveAck,
-                    data: buf[extension_start..payload_start].to_vec(),
-                };
-                extensions.push(extension);
+            // Guard against a hostile peer chaining an unbounded number of
+            // (or arbitrarily large) extensions to force unbounded allocation.
+            total_extension_bytes += len;
+            if extensions.len() >= MAX_EXTENSIONS || total_extension_bytes > MAX_EXTENSION_BYTES {
+                return Err(ParseError::TooManyExtensions);
             }
 
-            kind = buf[idx];
-            idx += len + 2;
+            let data = match cursor.decode_vec(len) {
+                Ok(data) => data,
+                Err(e) => return Err(e),
+            };
+            extensions.push(Extension {
+                ty: ExtensionType::from_u8(kind),
+                data: data,
+            });
+
+            kind = next_kind;
         }
         // Check for pending extensions (early exit of previous loop)
         if kind != 0 {
             return Err(ParseError::InvalidPacketLength);
         }
 
-        let mut payload;
-        if idx < buf.len() {
-            let payload_length = buf.len() - idx;
-            payload = Vec::with_capacity(payload_length);
-            unsafe {
-                use std::ptr;
-                ptr::copy(buf.as_ptr().offset(idx as isize), payload.as_mut_ptr(), payload_length);
-                payload.set_len(payload_length);
-            }
-        } else {
-            payload = Vec::new();
-        }
+        let payload = match cursor.decode_vec(cursor.remaining()) {
+            Ok(payload) => payload,
+            Err(e) => return Err(e),
+        };
 
         Ok(Packet {
             header: header,
@@ -371,7 +494,30 @@ mod tests {
     use super::Packet;
     use super::PacketType::{State, Data};
     use super::ExtensionType;
-    use super::HEADER_SIZE;
+    use super::{Decoder, HEADER_SIZE};
+
+    #[test]
+    fn test_decoder_reads_fields_and_tracks_remaining() {
+        let buf = [0xde, 0xad, 0xbe, 0xef, 0x01, 0x02, 0x03];
+        let mut decoder = Decoder::new(&buf);
+        assert_eq!(decoder.remaining(), buf.len());
+        assert_eq!(decoder.decode_u8().unwrap(), 0xde);
+        assert_eq!(decoder.decode_vec(3).unwrap(), vec!(0xad, 0xbe, 0xef));
+        assert_eq!(decoder.remaining(), 3);
+        assert_eq!(decoder.decode_vec(3).unwrap(), vec!(0x01, 0x02, 0x03));
+        assert_eq!(decoder.remaining(), 0);
+    }
+
+    #[test]
+    fn test_decoder_rejects_underrun() {
+        let buf = [0x01, 0x02];
+        let mut decoder = Decoder::new(&buf);
+        assert!(decoder.decode_vec(3).is_err());
+        // A failed read must not consume input.
+        assert_eq!(decoder.decode_u8().unwrap(), 0x01);
+        assert_eq!(decoder.decode_u8().unwrap(), 0x02);
+        assert!(decoder.decode_u8().is_err());
+    }
 
     #[test]
     fn test_packet_decode() {
@@ -419,6 +565,15 @@ mod tests {
         assert!(packet.extensions[0].len() == 5);
     }
 
+    #[test]
+    fn test_packet_decode_with_invalid_type() {
+        // Type nibble 5 doesn't correspond to any `PacketType` variant.
+        let buf = [0x51, 0x00, 0x41, 0xa8, 0x99, 0x2f, 0xd0, 0x2a, 0x9f, 0x4a,
+                   0x26, 0x21, 0x00, 0x10, 0x00, 0x00, 0x3a, 0xf2, 0x6c, 0x79];
+        let pkt = Packet::decode(&buf);
+        assert!(pkt.is_err());
+    }
+
     #[test]
     fn test_packet_decode_with_missing_extension() {
         let buf = [0x21, 0x01, 0x41, 0xa8, 0x99, 0x2f, 0xd0, 0x2a, 0x9f, 0x4a,
@@ -455,11 +610,48 @@ mod tests {
         assert_eq!(packet.seq_nr(), 43859);
         assert_eq!(packet.ack_nr(), 15093);
         assert!(packet.payload.is_empty());
-        assert!(packet.extensions.len() == 1);
+        assert!(packet.extensions.len() == 2);
         assert!(packet.extensions[0].ty == ExtensionType::SelectiveAck);
         assert!(packet.extensions[0].data == vec!(0,0,0,0));
         assert!(packet.extensions[0].len() == 1 + packet.extensions[0].data.len());
         assert!(packet.extensions[0].len() == 5);
+        assert!(packet.extensions[1].ty == ExtensionType::Unknown(0xff));
+        assert!(packet.extensions[1].data == vec!(0,0,0,0));
+        // Re-encoding must preserve the unknown extension and its position
+        // in the chain instead of silently dropping it.
+        assert_eq!(packet.bytes(), buf.to_vec());
+    }
+
+    #[test]
+    fn test_add_extension_and_extensions_of_type() {
+        let mut pkt = Packet::new();
+        pkt.add_extension(1, vec!(1,2,3,4));
+        pkt.add_extension(42, vec!(5,6,7,8));
+
+        assert_eq!(pkt.extensions.len(), 2);
+        let sacks: Vec<_> = pkt.extensions_of_type(ExtensionType::SelectiveAck).collect();
+        assert_eq!(sacks.len(), 1);
+        assert_eq!(sacks[0].data, vec!(1,2,3,4));
+        let unknown: Vec<_> = pkt.extensions_of_type(ExtensionType::Unknown(42)).collect();
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(unknown[0].data, vec!(5,6,7,8));
+    }
+
+    #[test]
+    fn test_add_extension_round_trip_preserves_first_kind() {
+        // `header.extension` must name only the first extension added, not
+        // a bitwise-OR of every kind in the chain.
+        let mut pkt = Packet::new();
+        pkt.add_extension(1, vec!(1,2,3,4));
+        pkt.add_extension(42, vec!(5,6,7,8));
+
+        let buf = pkt.bytes();
+        let decoded = Packet::decode(&buf).unwrap();
+        assert_eq!(decoded.extensions.len(), 2);
+        assert_eq!(decoded.extensions[0].get_type(), ExtensionType::SelectiveAck);
+        assert_eq!(decoded.extensions[0].data, vec!(1,2,3,4));
+        assert_eq!(decoded.extensions[1].get_type(), ExtensionType::Unknown(42));
+        assert_eq!(decoded.extensions[1].data, vec!(5,6,7,8));
     }
 
     #[test]
@@ -470,12 +662,12 @@ mod tests {
         let window_size: u32 = 1048576;
         let mut pkt = Packet::new();
         pkt.set_type(Data);
-        pkt.header.timestamp_microseconds = timestamp.to_be();
-        pkt.header.timestamp_difference_microseconds = timestamp_diff.to_be();
-        pkt.header.connection_id = connection_id.to_be();
-        pkt.header.seq_nr = seq_nr.to_be();
-        pkt.header.ack_nr = ack_nr.to_be();
-        pkt.header.wnd_size = window_size.to_be();
+        pkt.set_timestamp_microseconds(timestamp);
+        pkt.set_timestamp_difference_microseconds(timestamp_diff);
+        pkt.set_connection_id(connection_id);
+        pkt.set_seq_nr(seq_nr);
+        pkt.set_ack_nr(ack_nr);
+        pkt.set_wnd_size(window_size);
         pkt.payload = payload.clone();
         let header = pkt.header;
         let buf = [0x01, 0x00, 0x41, 0xa8, 0x00, 0xe9, 0x03, 0x89,
@@ -520,6 +712,59 @@ mod tests {
         assert!(packet.is_err());
     }
 
+    /// Build a packet whose extension chain is `count` back-to-back 4-byte
+    /// extensions, for exercising `MAX_EXTENSIONS`. Even 255 such extensions
+    /// (1020 bytes) stay within `MAX_EXTENSION_BYTES`.
+    fn packet_with_n_extensions(count: u8) -> Vec<u8> {
+        let mut buf = vec![0x21, if count > 0 { 1 } else { 0 }, 0x41, 0xa8,
+                            0x99, 0x2f, 0xd0, 0x2a, 0x9f, 0x4a,
+                            0x26, 0x21, 0x00, 0x10, 0x00, 0x00, 0x3a, 0xf2, 0x6c, 0x79];
+        for i in 0..count {
+            let next_kind = if i + 1 < count { 1 } else { 0 };
+            buf.push(next_kind);
+            buf.push(4);
+            buf.extend_from_slice(&[0, 0, 0, 0]);
+        }
+        buf
+    }
+
+    #[test]
+    fn test_decode_rejects_too_many_extensions() {
+        use quickcheck::{QuickCheck, TestResult};
+
+        fn run(count: u8) -> TestResult {
+            let buf = packet_with_n_extensions(count);
+            let packet = Packet::decode(&buf);
+            TestResult::from_bool(packet.is_ok() == ((count as usize) <= super::MAX_EXTENSIONS))
+        }
+        QuickCheck::new().tests(100).quickcheck(run as fn(u8) -> TestResult)
+    }
+
+    #[test]
+    fn test_decode_rejects_too_many_extension_bytes() {
+        // 5 extensions of 252 bytes each stay well under MAX_EXTENSIONS (8)
+        // but their combined size (1260 bytes) exceeds MAX_EXTENSION_BYTES
+        // (1024), so this must be rejected on the byte-count branch of the
+        // guard rather than the count branch.
+        let count = 5;
+        assert!(count <= super::MAX_EXTENSIONS);
+        let ext_len = 252;
+        assert!(count * ext_len > super::MAX_EXTENSION_BYTES);
+
+        let mut buf = vec![0x21, 1, 0x41, 0xa8,
+                           0x99, 0x2f, 0xd0, 0x2a, 0x9f, 0x4a,
+                           0x26, 0x21, 0x00, 0x10, 0x00, 0x00, 0x3a, 0xf2, 0x6c, 0x79];
+        for i in 0..count {
+            let next_kind = if i + 1 < count { 1 } else { 0 };
+            buf.push(next_kind as u8);
+            buf.push(ext_len as u8);
+            buf.extend(vec![0; ext_len]);
+        }
+
+        let packet = Packet::decode(&buf);
+        assert!(packet.is_err());
+    }
+
     // Use quickcheck to simulate a malicious attacker sending malformed packets
     #[test]
     fn quicktest() {
@@ -534,6 +779,9 @@ mod tests {
             } else if x[0] & 0x0F != 1 {
                 // Invalid version
                 TestResult::from_bool(packet.is_err())
+            } else if x[0] >> 4 > 4 {
+                // Invalid type
+                TestResult::from_bool(packet.is_err())
             } else if x[1] != 0 {
                 // Non-empty extension field, check validity of extension(s)
                 if x.len() < HEADER_SIZE + 2 {
@@ -542,6 +790,8 @@ mod tests {
 
                 let mut next_kind = x[1];
                 let mut idx = HEADER_SIZE;
+                let mut extension_count = 0usize;
+                let mut total_extension_bytes = 0usize;
 
                 while idx < x.len() && next_kind != 0 {
                     if x.len() < idx + 2 {
@@ -558,6 +808,16 @@ mod tests {
                         return TestResult::from_bool(packet.is_err());
                     }
 
+                    // `decode` also caps the number of extensions and their
+                    // combined size; a structurally valid chain can still be
+                    // rejected for exceeding either bound.
+                    extension_count += 1;
+                    total_extension_bytes += len;
+                    if extension_count > super::MAX_EXTENSIONS ||
+                       total_extension_bytes > super::MAX_EXTENSION_BYTES {
+                        return TestResult::from_bool(packet.is_err());
+                    }
+
                     idx += len + 2;
                 }
                 TestResult::from_bool(packet.is_ok() || next_kind != 0)